@@ -1,8 +1,10 @@
+use std::alloc;
 use std::io;
 use std::mem;
 use std::os::unix::io::RawFd;
 use std::ptr::{self, NonNull};
 use std::marker::PhantomData;
+use std::time::Duration;
 
 use super::{IoUring, sys};
 
@@ -12,6 +14,23 @@ const IORING_OP_WRITEV:             libc::__u8 = 2;
 const IORING_OP_FSYNC:              libc::__u8 = 3;
 const IORING_OP_READ_FIXED:         libc::__u8 = 4;
 const IORING_OP_WRITE_FIXED:        libc::__u8 = 5;
+const IORING_OP_POLL_ADD:           libc::__u8 = 6;
+const IORING_OP_POLL_REMOVE:        libc::__u8 = 7;
+const IORING_OP_SENDMSG:            libc::__u8 = 9;
+const IORING_OP_RECVMSG:            libc::__u8 = 10;
+const IORING_OP_TIMEOUT:            libc::__u8 = 11;
+const IORING_OP_FALLOCATE:          libc::__u8 = 17;
+const IORING_OP_OPENAT:             libc::__u8 = 18;
+const IORING_OP_CLOSE:              libc::__u8 = 19;
+const IORING_OP_STATX:              libc::__u8 = 21;
+const IORING_OP_READ:               libc::__u8 = 22;
+const IORING_OP_WRITE:              libc::__u8 = 23;
+const IORING_OP_FADVISE:            libc::__u8 = 24;
+const IORING_OP_MADVISE:            libc::__u8 = 25;
+const IORING_OP_SEND:               libc::__u8 = 26;
+const IORING_OP_RECV:               libc::__u8 = 27;
+const IORING_OP_OPENAT2:            libc::__u8 = 28;
+const IORING_OP_PROVIDE_BUFFERS:    libc::__u8 = 31;
 
 pub struct SubmissionQueue<'ring> {
     ring: NonNull<sys::io_uring>,
@@ -56,6 +75,74 @@ impl<'ring> SubmissionQueue<'ring> {
     }
 }
 
+pub(crate) const IO_URING_OP_SUPPORTED: libc::__u16 = 1 << 0;
+
+/// The number of `io_uring_probe_op` entries we ask the kernel to fill in. 256 covers every
+/// opcode defined as of the 5.6 kernel with room to spare for opcodes added later.
+const PROBE_OPS_LEN: usize = 256;
+
+fn probe_layout() -> alloc::Layout {
+    let len = mem::size_of::<sys::io_uring_probe>()
+        + PROBE_OPS_LEN * mem::size_of::<sys::io_uring_probe_op>();
+    alloc::Layout::from_size_align(len, mem::align_of::<sys::io_uring_probe>()).unwrap()
+}
+
+/// The set of opcodes the running kernel actually supports, as reported by
+/// `IORING_REGISTER_PROBE`. Obtained via [`IoUring::probe`].
+pub struct Probe(NonNull<sys::io_uring_probe>);
+
+impl Probe {
+    fn new() -> Probe {
+        let layout = probe_layout();
+        unsafe {
+            let ptr = alloc::alloc_zeroed(layout) as *mut sys::io_uring_probe;
+            Probe(NonNull::new(ptr).unwrap_or_else(|| alloc::handle_alloc_error(layout)))
+        }
+    }
+
+    pub fn is_supported(&self, opcode: u8) -> bool {
+        unsafe {
+            let probe = self.0.as_ref();
+            if opcode as usize >= probe.ops_len as usize {
+                return false;
+            }
+            let ops = probe.ops.as_slice(probe.ops_len as usize);
+            ops[opcode as usize].flags & IO_URING_OP_SUPPORTED != 0
+        }
+    }
+
+    pub fn last_op(&self) -> u8 {
+        unsafe { self.0.as_ref().last_op }
+    }
+}
+
+impl Drop for Probe {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.0.as_ptr() as *mut u8, probe_layout()) }
+    }
+}
+
+impl IoUring {
+    /// Ask the kernel which opcodes this ring supports, via `IORING_REGISTER_PROBE`. Lets
+    /// callers gate `prep_*` calls behind a capability check instead of failing at completion
+    /// time with `-EINVAL` on kernels that predate a given opcode.
+    pub fn probe(&self) -> io::Result<Probe> {
+        let mut probe = Probe::new();
+        let ret = unsafe {
+            sys::io_uring_register_probe(
+                &self.ring as *const sys::io_uring as *mut _,
+                probe.0.as_ptr(),
+                PROBE_OPS_LEN as _,
+            )
+        };
+        if ret >= 0 {
+            Ok(probe)
+        } else {
+            Err(io::Error::from_raw_os_error(ret))
+        }
+    }
+}
+
 pub struct SubmissionQueueEvent<'a> {
     sqe: &'a mut sys::io_uring_sqe,
 }
@@ -151,6 +238,212 @@ impl<'a> SubmissionQueueEvent<'a> {
         self.sqe.flags |= SubmissionFlags::FIXED_FILE.bits();
     }
 
+    #[inline]
+    pub unsafe fn prep_read(
+        &mut self,
+        fd: RawFd,
+        buf: &mut [u8],
+        offset: usize,
+    ) {
+        let len = buf.len();
+        let addr = buf as *mut [u8] as *mut libc::c_void;
+        self.sqe.opcode = IORING_OP_READ;
+        self.sqe.fd = fd;
+        self.sqe.off_addr2.off = offset as _;
+        self.sqe.addr = addr as _;
+        self.sqe.len = len as _;
+    }
+
+    #[inline]
+    pub unsafe fn prep_write(
+        &mut self,
+        fd: RawFd,
+        buf: &[u8],
+        offset: usize,
+    ) {
+        let len = buf.len();
+        let addr = buf as *const [u8] as *const libc::c_void;
+        self.sqe.opcode = IORING_OP_WRITE;
+        self.sqe.fd = fd;
+        self.sqe.off_addr2.off = offset as _;
+        self.sqe.addr = addr as _;
+        self.sqe.len = len as _;
+    }
+
+    #[inline]
+    pub unsafe fn prep_openat(
+        &mut self,
+        dfd: RawFd,
+        path: *const libc::c_char,
+        flags: i32,
+        mode: u32,
+    ) {
+        self.sqe.opcode = IORING_OP_OPENAT;
+        self.sqe.fd = dfd;
+        self.sqe.off_addr2.off = 0;
+        self.sqe.addr = path as _;
+        self.sqe.len = mode;
+        self.sqe.cmd_flags.open_flags = flags as _;
+    }
+
+    /// The caller must ensure `how` remains valid until the kernel has consumed this submission.
+    #[inline]
+    pub unsafe fn prep_openat2(
+        &mut self,
+        dfd: RawFd,
+        path: *const libc::c_char,
+        how: &sys::open_how,
+    ) {
+        self.sqe.opcode = IORING_OP_OPENAT2;
+        self.sqe.fd = dfd;
+        self.sqe.off_addr2.off = how as *const sys::open_how as _;
+        self.sqe.addr = path as _;
+        self.sqe.len = mem::size_of::<sys::open_how>() as _;
+    }
+
+    #[inline]
+    pub unsafe fn prep_close(&mut self, fd: RawFd) {
+        self.sqe.opcode = IORING_OP_CLOSE;
+        self.sqe.fd = fd;
+        self.sqe.off_addr2.off = 0;
+        self.sqe.addr = 0;
+        self.sqe.len = 0;
+    }
+
+    #[inline]
+    pub unsafe fn prep_statx(
+        &mut self,
+        dfd: RawFd,
+        path: *const libc::c_char,
+        flags: i32,
+        mask: u32,
+        statxbuf: *mut libc::statx,
+    ) {
+        self.sqe.opcode = IORING_OP_STATX;
+        self.sqe.fd = dfd;
+        self.sqe.off_addr2.off = statxbuf as _;
+        self.sqe.addr = path as _;
+        self.sqe.len = mask;
+        self.sqe.cmd_flags.statx_flags = flags as _;
+    }
+
+    #[inline]
+    pub unsafe fn prep_fallocate(&mut self, fd: RawFd, mode: i32, offset: u64, len: u64) {
+        self.sqe.opcode = IORING_OP_FALLOCATE;
+        self.sqe.fd = fd;
+        self.sqe.off_addr2.off = offset;
+        self.sqe.addr = len as _;
+        self.sqe.len = mode as _;
+    }
+
+    #[inline]
+    pub unsafe fn prep_fadvise(&mut self, fd: RawFd, offset: u64, len: usize, advice: i32) {
+        self.sqe.opcode = IORING_OP_FADVISE;
+        self.sqe.fd = fd;
+        self.sqe.off_addr2.off = offset;
+        self.sqe.addr = 0;
+        self.sqe.len = len as _;
+        self.sqe.cmd_flags.fadvise_advice = advice as _;
+    }
+
+    #[inline]
+    pub unsafe fn prep_madvise(&mut self, addr: *mut libc::c_void, len: usize, advice: i32) {
+        self.sqe.opcode = IORING_OP_MADVISE;
+        self.sqe.fd = -1;
+        self.sqe.off_addr2.off = 0;
+        self.sqe.addr = addr as _;
+        self.sqe.len = len as _;
+        self.sqe.cmd_flags.fadvise_advice = advice as _;
+    }
+
+    #[inline]
+    pub unsafe fn prep_send(&mut self, fd: RawFd, buf: &[u8], flags: i32) {
+        let len = buf.len();
+        let addr = buf as *const [u8] as *const libc::c_void;
+        self.sqe.opcode = IORING_OP_SEND;
+        self.sqe.fd = fd;
+        self.sqe.off_addr2.off = 0;
+        self.sqe.addr = addr as _;
+        self.sqe.len = len as _;
+        self.sqe.cmd_flags.msg_flags = flags as _;
+    }
+
+    #[inline]
+    pub unsafe fn prep_recv(&mut self, fd: RawFd, buf: &mut [u8], flags: i32) {
+        let len = buf.len();
+        let addr = buf as *mut [u8] as *mut libc::c_void;
+        self.sqe.opcode = IORING_OP_RECV;
+        self.sqe.fd = fd;
+        self.sqe.off_addr2.off = 0;
+        self.sqe.addr = addr as _;
+        self.sqe.len = len as _;
+        self.sqe.cmd_flags.msg_flags = flags as _;
+    }
+
+    #[inline]
+    pub unsafe fn prep_sendmsg(&mut self, fd: RawFd, msg: *const libc::msghdr, flags: i32) {
+        self.sqe.opcode = IORING_OP_SENDMSG;
+        self.sqe.fd = fd;
+        self.sqe.off_addr2.off = 0;
+        self.sqe.addr = msg as _;
+        self.sqe.len = 1;
+        self.sqe.cmd_flags.msg_flags = flags as _;
+    }
+
+    #[inline]
+    pub unsafe fn prep_recvmsg(&mut self, fd: RawFd, msg: *mut libc::msghdr, flags: i32) {
+        self.sqe.opcode = IORING_OP_RECVMSG;
+        self.sqe.fd = fd;
+        self.sqe.off_addr2.off = 0;
+        self.sqe.addr = msg as _;
+        self.sqe.len = 1;
+        self.sqe.cmd_flags.msg_flags = flags as _;
+    }
+
+    #[inline]
+    pub unsafe fn prep_poll_add(&mut self, fd: RawFd, events: PollFlags) {
+        self.sqe.opcode = IORING_OP_POLL_ADD;
+        self.sqe.fd = fd;
+        self.sqe.off_addr2.off = 0;
+        self.sqe.addr = 0;
+        self.sqe.len = 0;
+        self.sqe.cmd_flags.poll_events = events.bits();
+    }
+
+    #[inline]
+    pub unsafe fn prep_poll_remove(&mut self, target_user_data: u64) {
+        self.sqe.opcode = IORING_OP_POLL_REMOVE;
+        self.sqe.fd = -1;
+        self.sqe.off_addr2.off = 0;
+        self.sqe.addr = target_user_data as _;
+        self.sqe.len = 0;
+    }
+
+    #[inline]
+    pub unsafe fn prep_provide_buffers(
+        &mut self,
+        bufs: *mut u8,
+        buf_len: usize,
+        nbufs: u32,
+        buf_group: u16,
+        start_bid: u16,
+    ) {
+        self.sqe.opcode = IORING_OP_PROVIDE_BUFFERS;
+        self.sqe.fd = nbufs as _;
+        self.sqe.off_addr2.off = start_bid as _;
+        self.sqe.addr = bufs as _;
+        self.sqe.len = buf_len as _;
+        self.sqe.buf_index.buf_group = buf_group;
+    }
+
+    /// Select a buffer from `buf_group` at completion time instead of using the buffer
+    /// addressed by this submission. Also sets [`SubmissionFlags::BUFFER_SELECT`] on this
+    /// submission, which the kernel needs in order to select from the group.
+    pub fn set_buf_group(&mut self, buf_group: u16) {
+        self.sqe.buf_index.buf_group = buf_group;
+        self.sqe.flags |= SubmissionFlags::BUFFER_SELECT.bits();
+    }
+
     #[inline]
     pub unsafe fn prep_fsync(&mut self, fd: RawFd, flags: FsyncFlags) {
         self.sqe.opcode = IORING_OP_FSYNC;
@@ -161,6 +454,23 @@ impl<'a> SubmissionQueueEvent<'a> {
         self.sqe.cmd_flags.fsync_flags = flags.bits();
     }
 
+    /// The caller must ensure `ts` remains valid until the kernel has consumed this submission.
+    #[inline]
+    pub unsafe fn prep_timeout(
+        &mut self,
+        ts: &Timespec,
+        count: u32,
+        flags: TimeoutFlags,
+    ) {
+        let addr = &ts.0 as *const libc::__kernel_timespec as *const libc::c_void;
+        self.sqe.opcode = IORING_OP_TIMEOUT;
+        self.sqe.fd = -1;
+        self.sqe.off_addr2.off = count as _;
+        self.sqe.addr = addr as _;
+        self.sqe.len = 1;
+        self.sqe.cmd_flags.timeout_flags = flags.bits();
+    }
+
     #[inline]
     pub unsafe fn prep_nop(&mut self) {
         self.sqe.opcode = IORING_OP_NOP;
@@ -188,6 +498,7 @@ bitflags::bitflags! {
         const FIXED_FILE    = 1 << 0;   /* use fixed fileset */
         const IO_DRAIN      = 1 << 1;   /* issue after inflight IO */
         const IO_LINK       = 1 << 2;   /* next IO depends on this one */
+        const BUFFER_SELECT = 1 << 5;   /* select a buffer from a provided group */
     }
 }
 
@@ -196,3 +507,44 @@ bitflags::bitflags! {
         const FSYNC_DATASYNC    = 1 << 0;
     }
 }
+
+bitflags::bitflags! {
+    pub struct PollFlags: libc::c_ushort {
+        const POLLIN    = libc::POLLIN as _;
+        const POLLOUT   = libc::POLLOUT as _;
+        const POLLPRI   = libc::POLLPRI as _;
+        const POLLERR   = libc::POLLERR as _;
+        const POLLHUP   = libc::POLLHUP as _;
+        const POLLNVAL  = libc::POLLNVAL as _;
+    }
+}
+
+bitflags::bitflags! {
+    pub struct TimeoutFlags: libc::c_uint {
+        const ABS   = 1 << 0;   /* expiry is an absolute time */
+    }
+}
+
+/// An owned `timespec`, for use with [`SubmissionQueueEvent::prep_timeout`].
+///
+/// The kernel reads this value after the submission has been enqueued, so it must be kept
+/// alive (e.g. on the caller's stack or heap) until the corresponding completion arrives.
+pub struct Timespec(libc::__kernel_timespec);
+
+impl Timespec {
+    pub fn new() -> Timespec {
+        Timespec(unsafe { mem::zeroed() })
+    }
+
+    pub fn set(mut self, dur: Duration) -> Timespec {
+        self.0.tv_sec = dur.as_secs() as _;
+        self.0.tv_nsec = dur.subsec_nanos() as _;
+        self
+    }
+}
+
+impl Default for Timespec {
+    fn default() -> Timespec {
+        Timespec::new()
+    }
+}